@@ -1,13 +1,17 @@
 #[allow(unused_imports)]
 use flate2::write::GzEncoder;
 use flate2::Compression;
+use httpdate::{fmt_http_date, parse_http_date};
 use sha2::{Digest, Sha256};
 use std::path::PathBuf;
 
 use image::ImageOutputFormat;
 use std::io::Cursor;
-use std::net::TcpListener;
-use std::time::SystemTime;
+use std::net::{TcpListener, TcpStream};
+use std::process::Command;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
 use std::{
     fs,
     io::{Read, Write},
@@ -51,14 +55,22 @@ impl ImageCache {
         None
     }
 
+    /// Writes to a per-key temp file and renames it into place, so concurrent
+    /// requests racing on the same cache key never observe a partially
+    /// written (truncated) file via `get_cached`.
     fn store(&self, key: &str, data: &[u8]) -> std::io::Result<()> {
         let path = self.cache_dir.join(key);
-        fs::write(path, data)
+        let tmp_path = self.cache_dir.join(format!("{}.tmp", key));
+        fs::write(&tmp_path, data)?;
+        fs::rename(&tmp_path, &path)
     }
 }
 
 impl ImageOptions {
-    fn from_query(query: &str) -> Self {
+    /// Builds options from the query string, letting the negotiated `accept`
+    /// type (see `negotiate_format`) pick the output format by default; an
+    /// explicit `fmt=` in the query always wins over negotiation.
+    fn from_query(query: &str, accept: &str) -> Self {
         let params: Vec<(String, String)> = query
             .split('&')
             .filter_map(|param| {
@@ -74,6 +86,10 @@ impl ImageOptions {
             format: ImageOutputFormat::Jpeg(80), // default format
         };
 
+        if let Some(negotiated) = negotiate_format(accept) {
+            opts.format = negotiated;
+        }
+
         for (key, value) in params {
             match key.as_str() {
                 "w" | "width" => opts.width = value.parse().ok(),
@@ -113,6 +129,17 @@ impl ImageOptions {
     }
 }
 
+/// Picks an output format from a request's `Accept` header for server-driven
+/// content negotiation. Returns `None` when nothing we can produce is listed,
+/// leaving the caller's own default format in place.
+fn negotiate_format(accept: &str) -> Option<ImageOutputFormat> {
+    let accept = accept.to_lowercase();
+    if accept.contains("image/webp") {
+        return Some(ImageOutputFormat::WebP);
+    }
+    None
+}
+
 fn optimize_image(img_data: &[u8], options: &ImageOptions) -> Result<Vec<u8>, image::ImageError> {
     let img = image::load_from_memory(img_data)?;
 
@@ -138,20 +165,232 @@ fn optimize_image(img_data: &[u8], options: &ImageOptions) -> Result<Vec<u8>, im
     Ok(buffer)
 }
 
+#[derive(Debug, Clone, Copy)]
+enum VideoFormat {
+    Thumbnail(&'static str),
+    Transcode(&'static str),
+}
+
+#[derive(Debug)]
+struct VideoOptions {
+    seek_seconds: f64,
+    width: Option<u32>,
+    height: Option<u32>,
+    format: VideoFormat,
+}
+
+impl VideoOptions {
+    fn from_query(query: &str) -> Self {
+        let params: Vec<(String, String)> = query
+            .split('&')
+            .filter_map(|param| {
+                let mut parts = param.split('=');
+                Some((parts.next()?.to_string(), parts.next()?.to_string()))
+            })
+            .collect();
+
+        let mut opts = VideoOptions {
+            seek_seconds: 0.0,
+            width: None,
+            height: None,
+            format: VideoFormat::Thumbnail("jpg"), // default: a jpeg still frame
+        };
+
+        for (key, value) in params {
+            match key.as_str() {
+                "t" => opts.seek_seconds = value.parse().unwrap_or(0.0),
+                "w" | "width" => opts.width = value.parse().ok(),
+                "h" | "height" => opts.height = value.parse().ok(),
+                "fmt" => match value.as_str() {
+                    "jpg" | "jpeg" => opts.format = VideoFormat::Thumbnail("jpg"),
+                    "png" => opts.format = VideoFormat::Thumbnail("png"),
+                    "webp" => opts.format = VideoFormat::Thumbnail("webp"),
+                    "mp4" => opts.format = VideoFormat::Transcode("mp4"),
+                    "webm" => opts.format = VideoFormat::Transcode("webm"),
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+        opts
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self.format {
+            VideoFormat::Thumbnail("png") => "image/png",
+            VideoFormat::Thumbnail("webp") => "image/webp",
+            VideoFormat::Thumbnail(_) => "image/jpeg",
+            VideoFormat::Transcode("webm") => "video/webm",
+            VideoFormat::Transcode(_) => "video/mp4",
+        }
+    }
+
+    fn cache_key(&self, original_path: &str) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(original_path.as_bytes());
+        hasher.update(
+            format!(
+                "t{}w{:?}h{:?}fmt{:?}",
+                self.seek_seconds, self.width, self.height, self.format
+            )
+            .as_bytes(),
+        );
+        format!("{:x}", hasher.finalize())
+    }
+}
+
+fn max_video_input_bytes() -> u64 {
+    std::env::var("VIDEO_MAX_INPUT_BYTES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(200 * 1024 * 1024)
+}
+
+/// Extracts a thumbnail or transcodes a video via `ffmpeg`, guarding against
+/// unreasonably large inputs since every request shells out to an external process.
+fn process_video(input_path: &Path, options: &VideoOptions) -> Result<Vec<u8>, String> {
+    let metadata = fs::metadata(input_path).map_err(|e| e.to_string())?;
+    if metadata.len() > max_video_input_bytes() {
+        return Err(format!(
+            "input video exceeds maximum size of {} bytes",
+            max_video_input_bytes()
+        ));
+    }
+
+    let output_ext = match options.format {
+        VideoFormat::Thumbnail(fmt) => fmt,
+        VideoFormat::Transcode(fmt) => fmt,
+    };
+    // cache_key is intentionally shared across concurrent identical requests,
+    // so it alone can't name the output file: two threads processing the same
+    // video+options on a cache miss would race to read/delete each other's
+    // ffmpeg output. Add a per-call counter to keep the path unique.
+    static INVOCATION_COUNTER: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+    let invocation_id = INVOCATION_COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let output_path = std::env::temp_dir().join(format!(
+        "rusthttp-video-{}-{}-{}.{}",
+        std::process::id(),
+        options.cache_key(&input_path.to_string_lossy()),
+        invocation_id,
+        output_ext
+    ));
+
+    let mut cmd = Command::new("ffmpeg");
+    cmd.arg("-y");
+
+    if matches!(options.format, VideoFormat::Thumbnail(_)) {
+        cmd.arg("-ss").arg(options.seek_seconds.to_string());
+    }
+    cmd.arg("-i").arg(input_path);
+
+    match (options.width, options.height) {
+        (Some(w), Some(h)) => {
+            cmd.arg("-vf").arg(format!("scale={}:{}", w, h));
+        }
+        (Some(w), None) => {
+            cmd.arg("-vf").arg(format!("scale={}:-1", w));
+        }
+        (None, Some(h)) => {
+            cmd.arg("-vf").arg(format!("scale=-1:{}", h));
+        }
+        (None, None) => {}
+    }
+
+    match options.format {
+        VideoFormat::Thumbnail(_) => {
+            cmd.arg("-frames:v").arg("1");
+        }
+        VideoFormat::Transcode("webm") => {
+            cmd.arg("-c:v").arg("libvpx-vp9").arg("-c:a").arg("libopus");
+        }
+        VideoFormat::Transcode(_) => {
+            cmd.arg("-c:v").arg("libx264").arg("-c:a").arg("aac");
+        }
+    }
+
+    cmd.arg(&output_path);
+
+    let output = cmd
+        .output()
+        .map_err(|e| format!("failed to run ffmpeg: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "ffmpeg exited with an error: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    let result = fs::read(&output_path).map_err(|e| e.to_string())?;
+    let _ = fs::remove_file(&output_path);
+    Ok(result)
+}
+
+const KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Max bytes of request line + headers we'll buffer before giving up with a 400.
+const MAX_HEADER_BYTES: usize = 16 * 1024;
+
+fn worker_thread_count() -> usize {
+    std::env::var("HTTP_WORKER_THREADS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(16)
+}
+
+fn max_request_body_bytes() -> usize {
+    std::env::var("HTTP_MAX_BODY_BYTES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(64 * 1024 * 1024)
+}
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A fixed-size pool of worker threads that pull connections off a shared
+/// queue. Caps concurrent connection handling so a burst of (possibly idle,
+/// kept-alive) connections can't spawn unbounded OS threads.
+struct ThreadPool {
+    sender: mpsc::Sender<Job>,
+}
+
+impl ThreadPool {
+    fn new(size: usize) -> Self {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        for _ in 0..size {
+            let receiver = Arc::clone(&receiver);
+            thread::spawn(move || loop {
+                let job = receiver.lock().unwrap().recv();
+                match job {
+                    // Catch panics so a single bad job (e.g. an unexpected I/O
+                    // failure somewhere deep in request handling) can't shrink
+                    // the pool permanently — the worker keeps serving after.
+                    Ok(job) => {
+                        if std::panic::catch_unwind(std::panic::AssertUnwindSafe(job)).is_err() {
+                            eprintln!("worker thread: job panicked, continuing");
+                        }
+                    }
+                    Err(_) => break,
+                }
+            });
+        }
+
+        Self { sender }
+    }
+
+    fn execute<F: FnOnce() + Send + 'static>(&self, job: F) {
+        let _ = self.sender.send(Box::new(job));
+    }
+}
+
 fn main() {
     let listener = TcpListener::bind("127.0.0.1:4221").unwrap();
+    let pool = ThreadPool::new(worker_thread_count());
 
     for stream in listener.incoming() {
         match stream {
-            Ok(mut _stream) => {
-                let mut buffer = [0; 1024];
-                let bytes_read = _stream.read(&mut buffer).unwrap();
-
-                let request = String::from_utf8_lossy(&buffer[..bytes_read]);
-                let response = handle_request(&request);
-
-                _stream.write_all(&response).unwrap();
-                _stream.flush().unwrap();
+            Ok(stream) => {
+                pool.execute(move || handle_connection(stream));
             }
             Err(e) => {
                 println!("error: {}", e);
@@ -160,44 +399,245 @@ fn main() {
     }
 }
 
+/// Serves requests off one accepted connection, honoring HTTP/1.1 keep-alive:
+/// keeps reading and responding until the client (or we) ask to close, or the
+/// connection sits idle past `KEEP_ALIVE_TIMEOUT`.
+fn handle_connection(mut stream: TcpStream) {
+    let _ = stream.set_read_timeout(Some(KEEP_ALIVE_TIMEOUT));
+
+    loop {
+        let request = match parse_request(&mut stream) {
+            Ok(Some(request)) => request,
+            Ok(None) => break,
+            Err(ParseError::HeaderTooLarge) => {
+                let _ = stream.write_all(&build_response(400, Some("Request header too large")));
+                break;
+            }
+            Err(ParseError::BodyTooLarge) => {
+                let _ = stream.write_all(&build_response(413, Some("Request body too large")));
+                break;
+            }
+            Err(ParseError::Io(e)) => {
+                println!("error reading request: {}", e);
+                break;
+            }
+        };
+
+        let keep_alive = request.wants_keep_alive();
+        let mut response = handle_request(&request);
+        set_connection_header(&mut response, keep_alive);
+
+        if stream.write_all(&response).is_err() || stream.flush().is_err() {
+            break;
+        }
+
+        if !keep_alive {
+            break;
+        }
+    }
+}
+
 fn status_text(code: u32) -> &'static str {
     match code {
         200 => "OK",
         201 => "Created",
+        206 => "Partial Content",
+        304 => "Not Modified",
         400 => "Bad Request",
         403 => "Forbidden",
         404 => "Not Found",
+        413 => "Payload Too Large",
+        416 => "Range Not Satisfiable",
         500 => "Internal Server Error",
         _ => "Internal Server Error",
     }
 }
 
-fn handle_request(request: &str) -> Vec<u8> {
-    let first_line = request.lines().next().unwrap();
-    let mut parts = first_line.split_whitespace();
-    let method = parts.next().unwrap();
-    let full_path = parts.next().unwrap();
-    let _version = parts.next().unwrap();
+/// A fully-parsed HTTP request: the request line, headers, and a body read to
+/// exactly `Content-Length` bytes.
+struct Request {
+    method: String,
+    path: String,
+    query: Option<String>,
+    version: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Whether the client wants this connection kept open, per HTTP/1.1
+    /// semantics (keep-alive by default; HTTP/1.0 defaults to close).
+    fn wants_keep_alive(&self) -> bool {
+        match self.header("connection").map(|v| v.to_lowercase()) {
+            Some(v) if v == "close" => false,
+            Some(v) if v == "keep-alive" => true,
+            _ => self.version == "HTTP/1.1",
+        }
+    }
+}
+
+/// Why `parse_request` failed to produce a request.
+enum ParseError {
+    Io(std::io::Error),
+    /// Request line + headers exceeded `MAX_HEADER_BYTES` without a terminator.
+    HeaderTooLarge,
+    /// `Content-Length` exceeded `max_request_body_bytes()`.
+    BodyTooLarge,
+}
+
+impl From<std::io::Error> for ParseError {
+    fn from(e: std::io::Error) -> Self {
+        ParseError::Io(e)
+    }
+}
+
+/// Reads one full HTTP request off `stream`: the request line, headers, and
+/// exactly `Content-Length` bytes of body. Returns `Ok(None)` when the client
+/// closed the connection without sending anything, which is the normal way a
+/// keep-alive loop ends.
+fn parse_request(stream: &mut impl Read) -> Result<Option<Request>, ParseError> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 1024];
+
+    let header_end = loop {
+        if let Some(pos) = find_subslice(&buf, b"\r\n\r\n") {
+            break pos;
+        }
+        if buf.len() > MAX_HEADER_BYTES {
+            return Err(ParseError::HeaderTooLarge);
+        }
+        let bytes_read = stream.read(&mut chunk)?;
+        if bytes_read == 0 {
+            if buf.is_empty() {
+                return Ok(None);
+            }
+            break buf.len();
+        }
+        buf.extend_from_slice(&chunk[..bytes_read]);
+    };
+
+    let header_text = String::from_utf8_lossy(&buf[..header_end]).to_string();
+    let mut lines = header_text.lines();
+
+    let mut parts = lines.next().unwrap_or("").split_whitespace();
+    let method = parts.next().unwrap_or("").to_string();
+    let full_path = parts.next().unwrap_or("").to_string();
+    let version = parts.next().unwrap_or("HTTP/1.1").to_string();
+
+    let mut path_parts = full_path.splitn(2, '?');
+    let path = path_parts.next().unwrap_or("").to_string();
+    let query = path_parts.next().map(|q| q.to_string());
+
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            Some((name.trim().to_string(), value.trim().to_string()))
+        })
+        .collect();
+
+    let mut body = buf[(header_end + 4).min(buf.len())..].to_vec();
+    let content_length: usize = headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.parse().ok())
+        .unwrap_or(0);
+
+    if content_length > max_request_body_bytes() {
+        return Err(ParseError::BodyTooLarge);
+    }
+
+    while body.len() < content_length {
+        let bytes_read = stream.read(&mut chunk)?;
+        if bytes_read == 0 {
+            break;
+        }
+        body.extend_from_slice(&chunk[..bytes_read]);
+    }
+    body.truncate(content_length);
+
+    Ok(Some(Request {
+        method,
+        path,
+        query,
+        version,
+        headers,
+        body,
+    }))
+}
+
+fn find_subslice(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Inserts a `Connection` header into an already-built response, just before
+/// the blank line that separates headers from the body.
+fn set_connection_header(response: &mut Vec<u8>, keep_alive: bool) {
+    let value = if keep_alive { "keep-alive" } else { "close" };
+    if let Some(pos) = find_subslice(response, b"\r\n\r\n") {
+        let header = format!("Connection: {}\r\n", value).into_bytes();
+        response.splice(pos + 2..pos + 2, header);
+    }
+}
 
-    let mut path_parts = full_path.split('?');
-    let path = path_parts.next().unwrap();
-    let query = path_parts.next();
+fn handle_request(request: &Request) -> Vec<u8> {
+    println!(
+        "{} {} {}",
+        request.method,
+        request.path,
+        request.query.as_deref().unwrap_or("")
+    );
 
-    println!("{} {} {}", method, path, query.unwrap_or(""));
+    let accepts_gzip = request
+        .header("accept-encoding")
+        .is_some_and(|v| v.to_lowercase().contains("gzip"));
+    println!("Accepts gzip: {}", accepts_gzip);
 
-    let accepts_gzip = request.lines().any(|line| {
-        line.to_lowercase().starts_with("accept-encoding:") && line.to_lowercase().contains("gzip")
-    });
-    print!("Accepts gzip: {}\n", accepts_gzip);
+    let range_header = request.header("range");
+    let if_none_match = request.header("if-none-match");
+    let if_modified_since = request.header("if-modified-since");
+    let accept = request.header("accept").unwrap_or("");
 
-    match (method, path) {
-        ("GET", "/") => serve_file("/index.html", accepts_gzip, None),
-        ("GET", path) => serve_file(path, accepts_gzip, query),
+    match (request.method.as_str(), request.path.as_str()) {
+        ("PUT", "/upload") | ("POST", "/upload") => handle_upload(&request.body),
+        ("GET", "/") => serve_file(
+            "/index.html",
+            accepts_gzip,
+            None,
+            range_header,
+            if_none_match,
+            if_modified_since,
+            accept,
+        ),
+        ("GET", path) if path.starts_with("/blob/") => serve_blob(&path["/blob/".len()..]),
+        ("GET", path) => serve_file(
+            path,
+            accepts_gzip,
+            request.query.as_deref(),
+            range_header,
+            if_none_match,
+            if_modified_since,
+            accept,
+        ),
         _ => build_response(404, Some("Not found")),
     }
 }
 
-fn serve_file(path: &str, accepts_gzip: bool, query: Option<&str>) -> Vec<u8> {
+fn serve_file(
+    path: &str,
+    accepts_gzip: bool,
+    query: Option<&str>,
+    range_header: Option<&str>,
+    if_none_match: Option<&str>,
+    if_modified_since: Option<&str>,
+    accept: &str,
+) -> Vec<u8> {
     let file_path = Path::new("public").join(path.trim_start_matches('/'));
 
     if !file_path.starts_with("public") {
@@ -206,15 +646,28 @@ fn serve_file(path: &str, accepts_gzip: bool, query: Option<&str>) -> Vec<u8> {
 
     match fs::read(&file_path) {
         Ok(content) => {
-            let content_type = get_content_type(&file_path);
+            let content_type = detect_media_type(&content, &file_path);
+            let last_modified = file_last_modified(&file_path);
 
-            if is_image_content_type(content_type) && query.is_some() {
-                let options = ImageOptions::from_query(query.unwrap());
+            let wants_image_processing =
+                is_image_content_type(content_type) && (query.is_some() || negotiate_format(accept).is_some());
+            if wants_image_processing {
+                let options = ImageOptions::from_query(query.unwrap_or(""), accept);
 
-                let cache = ImageCache::new(PathBuf::from("cache/images"), 7)
-                    .unwrap_or_else(|_| panic!("Failed to create cache directory"));
+                let cache = match ImageCache::new(PathBuf::from("cache/images"), 7) {
+                    Ok(cache) => cache,
+                    Err(e) => {
+                        eprintln!("Failed to create image cache directory: {}", e);
+                        return build_response(500, Some("Failed to create cache directory"));
+                    }
+                };
 
                 let cache_key = options.cache_key(path);
+                let etag = format!("\"{}\"", cache_key);
+
+                if is_not_modified(if_none_match, if_modified_since, &etag, last_modified) {
+                    return build_not_modified_response(&etag, last_modified);
+                }
 
                 if let Some(cached_image) = cache.get_cached(&cache_key) {
                     println!("Cache hit for {}", path);
@@ -224,7 +677,14 @@ fn serve_file(path: &str, accepts_gzip: bool, query: Option<&str>) -> Vec<u8> {
                         ImageOutputFormat::WebP => "image/webp",
                         _ => content_type,
                     };
-                    return build_response_with_type(200, Some(&cached_image), new_content_type);
+                    return build_response_with_type_vary(
+                        200,
+                        Some(&cached_image),
+                        new_content_type,
+                        Some(&etag),
+                        last_modified,
+                        Some("Accept"),
+                    );
                 }
 
                 println!("Cache miss for {}", path);
@@ -240,24 +700,180 @@ fn serve_file(path: &str, accepts_gzip: bool, query: Option<&str>) -> Vec<u8> {
                             ImageOutputFormat::WebP => "image/webp",
                             _ => content_type,
                         };
-                        return build_response_with_type(200, Some(&optimized), new_content_type);
+                        return build_response_with_type_vary(
+                            200,
+                            Some(&optimized),
+                            new_content_type,
+                            Some(&etag),
+                            last_modified,
+                            Some("Accept"),
+                        );
                     }
                     Err(_) => return build_response(500, Some("Image processing failed")),
                 }
             }
 
+            if is_video_content_type(content_type) {
+                if let Some(query) = query {
+                    let options = VideoOptions::from_query(query);
+                    let new_content_type = options.content_type();
+
+                    let cache = match ImageCache::new(PathBuf::from("cache/videos"), 7) {
+                        Ok(cache) => cache,
+                        Err(e) => {
+                            eprintln!("Failed to create video cache directory: {}", e);
+                            return build_response(500, Some("Failed to create cache directory"));
+                        }
+                    };
+                    let cache_key = options.cache_key(path);
+                    let etag = format!("\"{}\"", cache_key);
+
+                    if is_not_modified(if_none_match, if_modified_since, &etag, last_modified) {
+                        return build_not_modified_response(&etag, last_modified);
+                    }
+
+                    let video_bytes = if let Some(cached) = cache.get_cached(&cache_key) {
+                        println!("Cache hit for {}", path);
+                        cached
+                    } else {
+                        println!("Cache miss for {}", path);
+                        match process_video(&file_path, &options) {
+                            Ok(result) => {
+                                if let Err(e) = cache.store(&cache_key, &result) {
+                                    eprintln!("Failed to cache video output: {}", e);
+                                }
+                                result
+                            }
+                            Err(e) => {
+                                eprintln!("Video processing failed: {}", e);
+                                return build_response(500, Some("Video processing failed"));
+                            }
+                        }
+                    };
+
+                    if let Some(range) = range_header {
+                        let total_len = video_bytes.len() as u64;
+                        return match parse_range(range, total_len) {
+                            Some((start, end)) if start < total_len && start <= end => {
+                                build_range_response(
+                                    &video_bytes,
+                                    start,
+                                    end,
+                                    total_len,
+                                    new_content_type,
+                                    &etag,
+                                    last_modified,
+                                )
+                            }
+                            Some(_) => build_range_not_satisfiable(total_len),
+                            None => build_response_with_type(
+                                200,
+                                Some(&video_bytes),
+                                new_content_type,
+                                Some(&etag),
+                                last_modified,
+                            ),
+                        };
+                    }
+
+                    return build_response_with_type(
+                        200,
+                        Some(&video_bytes),
+                        new_content_type,
+                        Some(&etag),
+                        last_modified,
+                    );
+                }
+            }
+
+            let etag = compute_etag(&content);
+            if is_not_modified(if_none_match, if_modified_since, &etag, last_modified) {
+                return build_not_modified_response(&etag, last_modified);
+            }
+
+            if let Some(range) = range_header {
+                let total_len = content.len() as u64;
+                return match parse_range(range, total_len) {
+                    Some((start, end)) if start < total_len && start <= end => {
+                        build_range_response(
+                            &content,
+                            start,
+                            end,
+                            total_len,
+                            content_type,
+                            &etag,
+                            last_modified,
+                        )
+                    }
+                    Some(_) => build_range_not_satisfiable(total_len),
+                    None => build_response_with_type(
+                        200,
+                        Some(&content),
+                        content_type,
+                        Some(&etag),
+                        last_modified,
+                    ),
+                };
+            }
+
             let should_compress =
                 accepts_gzip && is_compressible(content_type) && content.len() > 1024;
             if should_compress {
-                build_compressed_response(200, &content, content_type)
+                build_compressed_response(200, &content, content_type, &etag, last_modified)
             } else {
-                build_response_with_type(200, Some(&content), content_type)
+                build_response_with_type(
+                    200,
+                    Some(&content),
+                    content_type,
+                    Some(&etag),
+                    last_modified,
+                )
             }
         }
         Err(_) => build_response(404, Some("Not found")),
     }
 }
 
+fn file_last_modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).ok()?.modified().ok()
+}
+
+fn compute_etag(data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    format!("\"{:x}\"", hasher.finalize())
+}
+
+/// Checks `If-None-Match`/`If-Modified-Since` against the resource's current
+/// validators. `If-None-Match` takes precedence per RFC 7232 when both are present.
+fn is_not_modified(
+    if_none_match: Option<&str>,
+    if_modified_since: Option<&str>,
+    etag: &str,
+    last_modified: Option<SystemTime>,
+) -> bool {
+    if let Some(inm) = if_none_match {
+        return inm
+            .split(',')
+            .map(|candidate| candidate.trim())
+            .any(|candidate| candidate == "*" || candidate == etag);
+    }
+
+    if let (Some(ims), Some(modified)) = (if_modified_since, last_modified) {
+        if let Ok(since) = parse_http_date(ims) {
+            // HTTP-dates only carry whole-second precision, so round-trip
+            // `modified` through the same formatter before comparing —
+            // otherwise a non-zero sub-second component makes it always
+            // compare greater than `since`, even when nothing changed.
+            if let Ok(modified) = parse_http_date(&fmt_http_date(modified)) {
+                return modified <= since;
+            }
+        }
+    }
+
+    false
+}
+
 fn is_compressible(content_type: &str) -> bool {
     match content_type {
         "text/html"
@@ -279,11 +895,72 @@ fn get_content_type(path: &Path) -> &'static str {
         Some("png") => "image/png",
         Some("jpg") | Some("jpeg") => "image/jpeg",
         Some("gif") => "image/gif",
+        Some("svg") => "image/svg+xml",
+        Some("mp4") => "video/mp4",
+        Some("webm") => "video/webm",
+        Some("mov") => "video/quicktime",
         _ => "application/octet-stream",
     }
 }
 
-fn build_compressed_response(status_code: u32, content: &[u8], content_type: &str) -> Vec<u8> {
+/// Detects a file's media type from its leading bytes (magic signatures), falling
+/// back to `get_content_type`'s extension map when the bytes don't match a known
+/// signature. This lets extensionless or mislabeled image uploads still be
+/// recognized and routed into the image pipeline.
+fn detect_media_type(data: &[u8], path: &Path) -> &'static str {
+    if let Some(sniffed) = sniff_media_type(data) {
+        return sniffed;
+    }
+    get_content_type(path)
+}
+
+fn sniff_media_type(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        return Some("image/gif");
+    }
+    if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        return Some("image/jpeg");
+    }
+    if data.starts_with(&[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A]) {
+        return Some("image/png");
+    }
+    if data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        return Some("image/webp");
+    }
+    if data.starts_with(&[0x00, 0x00, 0x01, 0x00]) {
+        return Some("image/x-icon");
+    }
+
+    let leading = std::str::from_utf8(&data[..data.len().min(64)])
+        .unwrap_or("")
+        .trim_start();
+    if leading.starts_with("<?xml") || leading.starts_with("<svg") {
+        return Some("image/svg+xml");
+    }
+
+    None
+}
+
+/// Formats `ETag`/`Last-Modified` as ready-to-concatenate header lines (each
+/// ending in `\r\n`, or an empty string when the validator isn't available).
+fn validator_headers(etag: Option<&str>, last_modified: Option<SystemTime>) -> String {
+    let mut headers = String::new();
+    if let Some(etag) = etag {
+        headers.push_str(&format!("ETag: {}\r\n", etag));
+    }
+    if let Some(modified) = last_modified {
+        headers.push_str(&format!("Last-Modified: {}\r\n", fmt_http_date(modified)));
+    }
+    headers
+}
+
+fn build_compressed_response(
+    status_code: u32,
+    content: &[u8],
+    content_type: &str,
+    etag: &str,
+    last_modified: Option<SystemTime>,
+) -> Vec<u8> {
     let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
     encoder.write(content).unwrap();
     let compressed = encoder.finish().unwrap();
@@ -297,10 +974,12 @@ fn build_compressed_response(status_code: u32, content: &[u8], content_type: &st
          Content-Length: {}\r\n\
          Content-Encoding: gzip\r\n\
          Vary: Accept-Encoding\r\n\
+         {}\
          \r\n",
         status,
         content_type,
-        compressed.len()
+        compressed.len(),
+        validator_headers(Some(etag), last_modified)
     );
 
     let mut response = headers.into_bytes();
@@ -320,14 +999,46 @@ fn build_response(status_code: u32, body: Option<&str>) -> Vec<u8> {
     response.into_bytes()
 }
 
-fn build_response_with_type(status_code: u32, body: Option<&[u8]>, content_type: &str) -> Vec<u8> {
+fn build_response_with_type(
+    status_code: u32,
+    body: Option<&[u8]>,
+    content_type: &str,
+    etag: Option<&str>,
+    last_modified: Option<SystemTime>,
+) -> Vec<u8> {
+    build_response_with_type_vary(status_code, body, content_type, etag, last_modified, None)
+}
+
+/// Like `build_response_with_type`, but also emits a `Vary` header so caches
+/// know the response depends on more than just the request URL (e.g. content
+/// negotiated via `Accept`).
+fn build_response_with_type_vary(
+    status_code: u32,
+    body: Option<&[u8]>,
+    content_type: &str,
+    etag: Option<&str>,
+    last_modified: Option<SystemTime>,
+    vary: Option<&str>,
+) -> Vec<u8> {
     let body_content = body.unwrap_or(&[]);
     let status = format!("{} {}", status_code, status_text(status_code));
+    let accept_ranges = if status_code == 200 {
+        "Accept-Ranges: bytes\r\n"
+    } else {
+        ""
+    };
+    let vary_header = match vary {
+        Some(field) => format!("Vary: {}\r\n", field),
+        None => String::new(),
+    };
     let headers = format!(
-        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n",
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n{}{}{}\r\n",
         status,
         content_type,
-        body_content.len()
+        body_content.len(),
+        accept_ranges,
+        vary_header,
+        validator_headers(etag, last_modified)
     );
 
     let mut response = headers.into_bytes();
@@ -335,9 +1046,192 @@ fn build_response_with_type(status_code: u32, body: Option<&[u8]>, content_type:
     response
 }
 
+/// Builds a bodyless `304 Not Modified` response carrying the current validators.
+fn build_not_modified_response(etag: &str, last_modified: Option<SystemTime>) -> Vec<u8> {
+    let status = format!("304 {}", status_text(304));
+    let headers = format!(
+        "HTTP/1.1 {}\r\n{}\r\n",
+        status,
+        validator_headers(Some(etag), last_modified)
+    );
+    headers.into_bytes()
+}
+
+/// Parses a `Range: bytes=start-end` header into an inclusive `(start, end)` byte
+/// range. Returns `None` for anything we don't support (multipart ranges,
+/// malformed syntax) so the caller falls back to serving the full body. Returned
+/// ranges may still be unsatisfiable (`start >= total_len`); the caller is
+/// responsible for turning those into a 416.
+fn parse_range(header: &str, total_len: u64) -> Option<(u64, u64)> {
+    let spec = header.strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (start_str, end_str) = spec.split_once('-')?;
+
+    if start_str.is_empty() {
+        let suffix_len: u64 = end_str.parse().ok()?;
+        if suffix_len == 0 {
+            return None;
+        }
+        let start = total_len.saturating_sub(suffix_len);
+        return Some((start, total_len.saturating_sub(1)));
+    }
+
+    let start: u64 = start_str.parse().ok()?;
+    let end = if end_str.is_empty() {
+        total_len.saturating_sub(1)
+    } else {
+        end_str.parse::<u64>().ok()?.min(total_len.saturating_sub(1))
+    };
+    Some((start, end))
+}
+
+fn build_range_response(
+    content: &[u8],
+    start: u64,
+    end: u64,
+    total_len: u64,
+    content_type: &str,
+    etag: &str,
+    last_modified: Option<SystemTime>,
+) -> Vec<u8> {
+    let slice = &content[start as usize..=end as usize];
+    let status = format!("206 {}", status_text(206));
+    let headers = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\nAccept-Ranges: bytes\r\nContent-Length: {}\r\n{}\r\n",
+        status,
+        content_type,
+        start,
+        end,
+        total_len,
+        slice.len(),
+        validator_headers(Some(etag), last_modified)
+    );
+
+    let mut response = headers.into_bytes();
+    response.extend_from_slice(slice);
+    response
+}
+
+fn build_range_not_satisfiable(total_len: u64) -> Vec<u8> {
+    let status = format!("416 {}", status_text(416));
+    format!(
+        "HTTP/1.1 {}\r\nContent-Range: bytes */{}\r\nContent-Length: 0\r\n\r\n",
+        status, total_len
+    )
+    .into_bytes()
+}
+
 fn is_image_content_type(content_type: &str) -> bool {
     matches!(
         content_type,
         "image/jpeg" | "image/png" | "image/webp" | "image/gif"
     )
 }
+
+fn is_video_content_type(content_type: &str) -> bool {
+    matches!(content_type, "video/mp4" | "video/webm" | "video/quicktime")
+}
+
+const BLOBS_DIR: &str = "blobs";
+
+/// Stores an uploaded blob under `blobs/<sha256>.<ext>`, deduplicating by hash.
+/// Returns `201 Created` for a new blob or `200 OK` when the content already exists.
+fn max_upload_bytes() -> usize {
+    std::env::var("UPLOAD_MAX_BYTES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(25 * 1024 * 1024)
+}
+
+fn handle_upload(body: &[u8]) -> Vec<u8> {
+    if body.is_empty() {
+        return build_response(400, Some("Empty body"));
+    }
+
+    if body.len() > max_upload_bytes() {
+        return build_response(413, Some("Upload exceeds maximum allowed size"));
+    }
+
+    let mut hasher = Sha256::new();
+    hasher.update(body);
+    let hash = format!("{:x}", hasher.finalize());
+
+    let content_type = detect_media_type(body, Path::new(""));
+    let ext = extension_for_content_type(content_type);
+
+    if let Err(e) = fs::create_dir_all(BLOBS_DIR) {
+        eprintln!("Failed to create blobs directory: {}", e);
+        return build_response(500, Some("Failed to store blob"));
+    }
+
+    let blob_path = Path::new(BLOBS_DIR).join(format!("{}.{}", hash, ext));
+    let status = if blob_path.exists() {
+        200
+    } else {
+        let tmp_path = Path::new(BLOBS_DIR).join(format!("{}.tmp", hash));
+        if let Err(e) = fs::write(&tmp_path, body) {
+            eprintln!("Failed to write blob: {}", e);
+            return build_response(500, Some("Failed to store blob"));
+        }
+        if let Err(e) = fs::rename(&tmp_path, &blob_path) {
+            eprintln!("Failed to finalize blob: {}", e);
+            return build_response(500, Some("Failed to store blob"));
+        }
+        201
+    };
+
+    let json = format!(
+        "{{\"sha256\":\"{}\",\"size\":{},\"type\":\"{}\"}}",
+        hash,
+        body.len(),
+        content_type
+    );
+    build_response_with_type(status, Some(json.as_bytes()), "application/json", None, None)
+}
+
+/// Serves a previously uploaded blob by its SHA-256 hex digest.
+fn serve_blob(hash: &str) -> Vec<u8> {
+    if hash.is_empty() || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        return build_response(400, Some("Invalid blob hash"));
+    }
+
+    let entries = match fs::read_dir(BLOBS_DIR) {
+        Ok(entries) => entries,
+        Err(_) => return build_response(404, Some("Not found")),
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.file_stem().and_then(|s| s.to_str()) != Some(hash) {
+            continue;
+        }
+        if let Ok(content) = fs::read(&path) {
+            let content_type = detect_media_type(&content, &path);
+            let etag = compute_etag(&content);
+            let last_modified = file_last_modified(&path);
+            return build_response_with_type(
+                200,
+                Some(&content),
+                content_type,
+                Some(&etag),
+                last_modified,
+            );
+        }
+    }
+
+    build_response(404, Some("Not found"))
+}
+
+fn extension_for_content_type(content_type: &str) -> &'static str {
+    match content_type {
+        "image/jpeg" => "jpg",
+        "image/png" => "png",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        "image/x-icon" => "ico",
+        "image/svg+xml" => "svg",
+        _ => "bin",
+    }
+}